@@ -1,11 +1,12 @@
 use clap::Subcommand;
 use git2::{FetchOptions, Repository};
-use semver::Version;
+use semver::{Version, VersionReq};
 use std::{
+  collections::{HashMap, HashSet},
   error::Error,
   fs::{File, OpenOptions},
   io::Write,
-  path::Path,
+  path::{Path, PathBuf},
 };
 use toml_edit::{value, DocumentMut, Item, Table, TableLike};
 
@@ -24,21 +25,83 @@ pub enum PackageCmd {
     version: Option<String>,
     #[arg(short = 'a', long, help = "Dependency alias")]
     alias: Option<String>,
+    #[arg(short = 'b', long, conflicts_with = "version", help = "Track a branch instead of a tag")]
+    branch: Option<String>,
+    #[arg(long, conflicts_with = "version", conflicts_with = "branch", help = "Pin an exact commit instead of a tag")]
+    rev: Option<String>,
+    #[arg(long, help = "Ignore 'mod.lock' and re-resolve the reference")]
+    update: bool,
   },
   /// Removes a dependency
   Remove {
     #[arg(help = "Name of the dependency to remove")]
     name: String,
   },
-  Tidy,
+  /// Reconciles '.bend/' with 'mod.toml'
+  Tidy {
+    #[arg(long, help = "Ignore 'mod.lock' and re-resolve every dependency")]
+    update: bool,
+  },
+}
+
+/// The Git ref a dependency is pinned to: a released tag, a semver
+/// requirement to pick the best published tag for, a tracked branch, a
+/// pinned commit, or (until resolved) whatever the latest tag turns out to be.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GitReference {
+  Tag(String),
+  Req(String),
+  Branch(String),
+  Rev(String),
+  Default,
+}
+
+impl GitReference {
+  /// Builds a `GitReference` from the mutually exclusive `Get` CLI args.
+  fn from_args(version: Option<String>, branch: Option<String>, rev: Option<String>) -> Self {
+    match (version, branch, rev) {
+      (Some(version), None, None) => GitReference::from_version_string(version),
+      (None, Some(branch), None) => GitReference::Branch(branch),
+      (None, None, Some(rev)) => GitReference::Rev(rev),
+      (None, None, None) => GitReference::Default,
+      _ => unreachable!("clap enforces version/branch/rev are mutually exclusive"),
+    }
+  }
+
+  /// A bare version (`1.2.0`) is an exact tag, same as always; anything that
+  /// looks like a semver requirement (`^1.2`, `~1.2.0`, `>=1.0, <2.0`, ...)
+  /// is instead resolved against the published tags at checkout time.
+  fn from_version_string(version: String) -> Self {
+    if is_semver_requirement(&version) {
+      GitReference::Req(version)
+    } else {
+      GitReference::Tag(version)
+    }
+  }
+
+  /// The `mod.toml` key this reference is recorded under.
+  fn toml_key(&self) -> &'static str {
+    match self {
+      GitReference::Tag(_) | GitReference::Req(_) | GitReference::Default => "version",
+      GitReference::Branch(_) => "branch",
+      GitReference::Rev(_) => "rev",
+    }
+  }
+}
+
+/// Whether a version string is a semver requirement rather than an exact tag.
+fn is_semver_requirement(version: &str) -> bool {
+  version.chars().any(|c| matches!(c, '^' | '~' | '>' | '<' | '*' | ',' | ' '))
 }
 
 pub fn handle_package_cmd(command: PackageCmd) -> Result<(), Box<dyn Error>> {
   match command {
     PackageCmd::Init { name } => init(&name),
-    PackageCmd::Get { name, version, alias } => get(&name, version, alias),
+    PackageCmd::Get { name, version, alias, branch, rev, update } => {
+      get(&name, GitReference::from_args(version, branch, rev), alias, update)
+    }
     PackageCmd::Remove { name } => remove(&name),
-    PackageCmd::Tidy {} => todo!(),
+    PackageCmd::Tidy { update } => tidy(update),
   }
 }
 
@@ -50,58 +113,256 @@ fn init(name: &str) -> Result<(), Box<dyn Error>> {
   Ok(())
 }
 
-/// Clones or updates a Git repository, checks out a specific version (if provided),
-/// and updates the module configuration file with the dependency information.
-fn get(name: &str, version: Option<String>, alias: Option<String>) -> Result<(), Box<dyn Error>> {
-  let url = format!("https://{name}.git");
+/// Installs `name` at the requested reference, together with everything its
+/// own `mod.toml` transitively depends on, and records the result in
+/// `mod.toml` (the direct dependency only) and `mod.lock` (the whole graph).
+fn get(name: &str, reference: GitReference, alias: Option<String>, update: bool) -> Result<(), Box<dyn Error>> {
+  let lockfile = get_lockfile()?;
+  let installs = install_graph(vec![(name.to_string(), reference, alias.clone())], &lockfile, update)?;
+
+  let root = installs.iter().find(|install| install.name == name).expect("root is always installed");
+  update_mod(name, &root.requirement, alias)?;
+
+  let mut lockfile = lockfile;
+  for install in &installs {
+    let url = dependency_url(&install.name);
+    lockfile.insert(&install.name, lock_entry(&install.resolved, &url, install.commit));
+  }
+  save_lockfile(lockfile)
+}
+
+/// Folder a dependency named `name` (with optional `alias`) is checked out to.
+fn dependency_path(name: &str, alias: Option<&str>) -> PathBuf {
+  Path::new(".bend").join(alias.unwrap_or_else(|| repository_name(name)))
+}
+
+/// The outcome of installing one node of the dependency graph: `requirement`
+/// is what gets persisted to `mod.toml` (a `Req` survives unresolved, so it's
+/// re-evaluated against whatever's published next time), `resolved` is the
+/// concrete tag/branch/rev that was actually checked out and goes in `mod.lock`.
+struct Install {
+  name: String,
+  requirement: GitReference,
+  resolved: GitReference,
+  alias: Option<String>,
+  commit: git2::Oid,
+  added: bool,
+  updated: bool,
+}
+
+/// Installs `roots` and everything they transitively depend on. Each
+/// dependency's own `mod.toml` is read after it's checked out and its
+/// `dependencies` table is walked too, deduplicating by `repository_key` (the
+/// resolved repo identity, not the raw name string some other `mod.toml` may
+/// spell differently) so the same physical repo visited twice only has its
+/// requirements unified, not cloned into a second folder, and is walked again
+/// only when unification changed its requirement — which also keeps cycles
+/// from looping forever. The first name a key is seen
+/// under is the one used for its `.bend/` folder and URL from then on, so a
+/// re-walk under a different spelling still lands on the same checkout.
+fn install_graph(
+  roots: Vec<(String, GitReference, Option<String>)>,
+  lockfile: &DocumentMut,
+  update: bool,
+) -> Result<Vec<Install>, Box<dyn Error>> {
+  let mut requirements: HashMap<String, (String, GitReference, Option<String>)> = HashMap::new();
+  let mut before: HashMap<String, Option<git2::Oid>> = HashMap::new();
+  let mut results: HashMap<String, (String, GitReference, GitReference, Option<String>, git2::Oid)> = HashMap::new();
+  let mut visited: HashSet<String> = HashSet::new();
+  let mut queue = roots;
+
+  while let Some((name, reference, alias)) = queue.pop() {
+    let key = repository_key(&name);
+    let previous = requirements.get(&key).cloned();
+    let name = previous.as_ref().map_or_else(|| name.clone(), |(name, ..)| name.clone());
+    let reference = match &previous {
+      Some((_, existing, _)) => unify_references(&name, existing.clone(), reference)?,
+      None => reference,
+    };
+    let alias = alias.or_else(|| previous.as_ref().and_then(|(_, _, alias)| alias.clone()));
+    requirements.insert(key.clone(), (name.clone(), reference.clone(), alias.clone()));
+
+    // Re-walk a node whenever unification just raised its requirement, even
+    // if it was already visited, so dependencies added only at the higher
+    // version aren't silently dropped; skip only when nothing changed.
+    let unvisited = visited.insert(key.clone());
+    if !unvisited && previous.is_some_and(|(_, existing, _)| existing == reference) {
+      continue;
+    }
+
+    let url = dependency_url(&name);
+    let local_path = dependency_path(&name, alias.as_deref());
+    if unvisited {
+      before.insert(key.clone(), current_commit(&local_path));
+    }
+
+    let locked = (!update).then(|| locked_entry(lockfile, &name)).flatten();
+    let locked = locked.as_ref().map(|(version, commit)| (version.as_str(), commit.as_str()));
+    let (requirement, resolved, commit) = setup_repo(&local_path, &url, reference, locked)?;
+    results.insert(key, (name, requirement, resolved, alias, commit));
+
+    for (dep_name, dep_reference) in read_dependencies(&local_path)? {
+      queue.push((dep_name, dep_reference, None));
+    }
+  }
+
+  Ok(
+    results
+      .into_iter()
+      .map(|(key, (name, requirement, resolved, alias, commit))| {
+        let added = before[&key].is_none();
+        let updated = !added && before[&key] != Some(commit);
+        Install { name, requirement, resolved, alias, commit, added, updated }
+      })
+      .collect(),
+  )
+}
+
+/// A stable identity for the repository `name` refers to, derived from the
+/// resolved clone URL rather than the raw dependency-name spelling, so the
+/// same physical repo named two different ways (a bare `user/repo` pulled in
+/// directly vs. a full URL or `scp`-style address pulled in transitively)
+/// dedupes to one node instead of racing over the same `.bend/` folder.
+fn repository_key(name: &str) -> String {
+  let url = dependency_url(name);
+  let url = url.strip_suffix(".git").unwrap_or(&url);
+  let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+  match url.strip_prefix("git@") {
+    Some(address) => address.replacen(':', "/", 1),
+    None => url.to_string(),
+  }
+}
+
+/// Unifies two requirements on the same dependency, preferring whichever one
+/// is actually pinned, picking the higher of two tags, and keeping an exact
+/// tag that already satisfies a semver requirement. Anything else
+/// (conflicting branches, revs, or a tag outside the requirement) is a hard error.
+fn unify_references(name: &str, a: GitReference, b: GitReference) -> Result<GitReference, Box<dyn Error>> {
+  match (a, b) {
+    (GitReference::Default, other) | (other, GitReference::Default) => Ok(other),
+    (a, b) if a == b => Ok(a),
+    (GitReference::Tag(a), GitReference::Tag(b)) => match (Version::parse(&a), Version::parse(&b)) {
+      (Ok(va), Ok(vb)) => Ok(GitReference::Tag(if va >= vb { a } else { b })),
+      _ => Err(format!("conflicting version requirement on '{name}': '{a}' vs '{b}'").into()),
+    },
+    (GitReference::Tag(tag), GitReference::Req(req)) | (GitReference::Req(req), GitReference::Tag(tag)) => {
+      match (Version::parse(&tag), VersionReq::parse(&req)) {
+        (Ok(version), Ok(parsed)) if parsed.matches(&version) => Ok(GitReference::Tag(tag)),
+        _ => Err(format!("conflicting requirement on '{name}': version '{tag}' does not satisfy requirement '{req}'").into()),
+      }
+    }
+    (a, b) => {
+      Err(format!("conflicting requirement on '{name}': {} vs {}", describe_reference(&a), describe_reference(&b)).into())
+    }
+  }
+}
+
+fn describe_reference(reference: &GitReference) -> String {
+  match reference {
+    GitReference::Tag(tag) => format!("version '{tag}'"),
+    GitReference::Req(req) => format!("requirement '{req}'"),
+    GitReference::Branch(branch) => format!("branch '{branch}'"),
+    GitReference::Rev(rev) => format!("commit '{rev}'"),
+    GitReference::Default => "the latest tag".to_string(),
+  }
+}
 
-  let repo_name = alias.as_deref().unwrap_or_else(|| repository_name(name));
-  let folder = format!(".bend/{}", repo_name);
-  let local_path = Path::new(&folder);
+/// Reads the `dependencies` table out of another project's `mod.toml`, if it
+/// has one, in the same `(name, reference)` shape `get` persists them in.
+fn read_dependencies(local_path: &Path) -> Result<Vec<(String, GitReference)>, Box<dyn Error>> {
+  let path = local_path.join("mod.toml");
+  let Ok(file) = std::fs::read_to_string(&path) else {
+    return Ok(Vec::new());
+  };
 
-  let tag = setup_repo(local_path, &url, version)?;
+  let config = file.parse::<DocumentMut>().map_err(|_| format!("invalid 'mod.toml' format in '{}'", path.display()))?;
+  let Some(deps) = config.get("dependencies").and_then(Item::as_table_like) else {
+    return Ok(Vec::new());
+  };
 
-  update_mod(name, &tag, alias)
+  Ok(deps.iter().map(|(name, item)| (name.to_string(), dependency_spec(item).0)).collect())
 }
 
-/// Extracts the repository name from a full repository URL.
-/// Assumes the URL is in the format `user/repo`.
+/// Extracts the repository name to use as the `.bend/` checkout folder (and
+/// in `tidy`'s report) from a dependency name: the last `/`-separated path
+/// segment, falling back to the part after `:` for a slash-less scp-style
+/// name (`git@host:repo`), with any trailing `.git` stripped.
 fn repository_name(name: &str) -> &str {
-  let (_user, repo) = name.rsplit_once('/').expect("Invalid repository URL");
-  repo
+  let repo = match name.rsplit_once('/') {
+    Some((_, repo)) => repo,
+    None => name.rsplit_once(':').map_or(name, |(_, repo)| repo),
+  };
+  repo.strip_suffix(".git").unwrap_or(repo)
 }
 
-/// Sets up the repository at the given local path, cloning it if it doesn't exist,
-/// and checks out the specified version or the latest tag.
-fn setup_repo(local_path: &Path, url: &str, version: Option<String>) -> Result<String, Box<dyn Error>> {
+/// Builds the URL to clone a dependency from. A dependency name that's
+/// already a full URL or an `scp`-style SSH address (`git@host:user/repo`)
+/// is used as-is, so modules can be hosted anywhere; a bare `user/repo` is
+/// still assumed to be a GitHub HTTPS clone.
+fn dependency_url(name: &str) -> String {
+  if name.contains("://") || name.starts_with("git@") {
+    name.to_string()
+  } else {
+    format!("https://{name}.git")
+  }
+}
+
+/// Sets up the repository at the given local path, cloning it if it doesn't exist.
+/// When `locked` (the `mod.lock` entry's `(version, commit)`) is given, skips
+/// resolution entirely and fetches+checks out that exact commit — the whole
+/// point of the lockfile is to avoid a `latest`/semver-requirement resolution
+/// (and the full tag fetch it requires) on every `get`/`tidy`. Otherwise
+/// resolves the requested reference (`Default` to the latest tag, `Req` to the
+/// highest tag that satisfies it) and checks that out. Returns the reference
+/// to persist in `mod.toml` (a `Req` is kept as-is, so it's re-resolved on the
+/// next `get`/`tidy`), the concrete reference that was actually checked out,
+/// and the commit to lock.
+fn setup_repo(
+  local_path: &Path,
+  url: &str,
+  reference: GitReference,
+  locked: Option<(&str, &str)>,
+) -> Result<(GitReference, GitReference, git2::Oid), Box<dyn Error>> {
   // Check if the repository already exists
   let repo = match Repository::open(local_path) {
     Ok(repo) => repo,
     Err(_) => Repository::init(local_path)?,
   };
 
-  setup_remote(&repo, url, "origin")?;
+  if let Some((version, commit)) = locked {
+    setup_remote(&repo, url, "origin", &GitReference::Rev(commit.to_string()))?;
+    checkout_rev(&repo, commit).map_err(|err| describe_checkout_error(err, &format!("Locked commit '{commit}'"), url))?;
+    let commit = repo.head()?.peel_to_commit()?.id();
+    let persisted = match reference {
+      GitReference::Default => GitReference::Tag(version.to_string()),
+      other => other,
+    };
+    return Ok((persisted, GitReference::Tag(version.to_string()), commit));
+  }
+
+  setup_remote(&repo, url, "origin", &reference)?;
 
-  // Determine the tag to checkout
-  let tag = match version {
-    Some(ver) => ver,
-    None => get_latest_tag(&repo)?,
+  let resolved = match &reference {
+    GitReference::Default => GitReference::Tag(get_latest_tag(&repo)?),
+    GitReference::Req(req) => GitReference::Tag(resolve_requirement(&repo, url, req)?),
+    _ => reference.clone(),
   };
 
-  // Checkout the specified tag
-  if let Err(err) = checkout_tag(&repo, &tag) {
-    match err.class() {
-      git2::ErrorClass::Reference => return Err(format!("Version '{tag}' not found on '{url}'").into()),
-      _ => return Err(err.message().into()),
-    }
-  }
+  let persisted = match reference {
+    GitReference::Default => resolved.clone(),
+    other => other,
+  };
+
+  checkout_reference(&repo, url, &resolved)?;
 
-  Ok(tag)
+  let commit = repo.head()?.peel_to_commit()?.id();
+
+  Ok((persisted, resolved, commit))
 }
 
 /// Sets up the remote URL for the repository, updating it if necessary,
-/// and fetches all tags.
-fn setup_remote(repo: &Repository, url: &str, remote_name: &str) -> Result<(), Box<dyn Error>> {
+/// and fetches whatever refs are needed to resolve `reference`.
+fn setup_remote(repo: &Repository, url: &str, remote_name: &str, reference: &GitReference) -> Result<(), Box<dyn Error>> {
   let remote = match repo.find_remote(remote_name) {
     Ok(remote) if remote.url() != Some(url) => {
       repo.remote_set_url(remote_name, url)?;
@@ -111,15 +372,79 @@ fn setup_remote(repo: &Repository, url: &str, remote_name: &str) -> Result<(), B
     Err(_) => repo.remote(remote_name, url)?,
   };
 
-  refresh_tags(repo, remote)
+  refresh_refs(repo, remote, reference)
+}
+
+/// Fetches only the refs needed to resolve `reference`. When the exact tag,
+/// branch, or commit is already known, this is a single-ref, depth-1 fetch;
+/// resolving `latest` still needs the full tag namespace to compare versions,
+/// and a raw commit rev falls back to a full branch fetch if the server
+/// doesn't allow fetching a bare SHA.
+fn refresh_refs(repo: &Repository, mut remote: git2::Remote, reference: &GitReference) -> Result<(), Box<dyn Error>> {
+  match reference {
+    GitReference::Default | GitReference::Req(_) => refresh_all_tags(repo, &mut remote)?,
+    GitReference::Tag(tag) => {
+      let refspec = format!("refs/tags/{tag}:refs/tags/{tag}");
+      let mut opts = authenticated_fetch_options();
+      opts.depth(1);
+      if remote.fetch(&[&refspec], Some(&mut opts), None).is_err() {
+        refresh_all_tags(repo, &mut remote)?;
+      }
+    }
+    GitReference::Branch(branch) => {
+      let refspec = format!("refs/heads/{branch}:refs/remotes/origin/{branch}");
+      let mut opts = authenticated_fetch_options();
+      opts.depth(1);
+      remote.fetch(&[&refspec], Some(&mut opts), None)?;
+    }
+    GitReference::Rev(rev) => {
+      let mut opts = authenticated_fetch_options();
+      opts.depth(1);
+      if remote.fetch(&[rev.as_str()], Some(&mut opts), None).is_err() {
+        remote.fetch(&["refs/heads/*:refs/remotes/origin/*"], Some(&mut authenticated_fetch_options()), None)?;
+      }
+    }
+  }
+  Ok(())
 }
 
-/// Refreshes the tags for the repository by deleting local tags and fetching remote tags.
-fn refresh_tags(repo: &Repository, mut remote: git2::Remote) -> Result<(), Box<dyn Error>> {
+/// Fetches the complete tag namespace, which resolving `latest` needs in
+/// order to compare every published version.
+fn refresh_all_tags(repo: &Repository, remote: &mut git2::Remote) -> Result<(), git2::Error> {
   delete_local_tags(repo)?;
+  remote.fetch(&["refs/tags/*:refs/tags/*"], Some(&mut authenticated_fetch_options()), None)
+}
+
+/// Builds fetch options with credentials wired in: ssh-agent and the default
+/// key pair for SSH remotes, and a token from `BEND_GIT_TOKEN` for HTTPS
+/// remotes that need one.
+fn authenticated_fetch_options<'a>() -> FetchOptions<'a> {
+  let mut callbacks = git2::RemoteCallbacks::new();
+  callbacks.credentials(|_url, username_from_url, allowed_types| {
+    if allowed_types.contains(git2::CredentialType::SSH_KEY) {
+      let username = username_from_url.unwrap_or("git");
+      if let Ok(cred) = git2::Cred::ssh_key_from_agent(username) {
+        return Ok(cred);
+      }
+      return git2::Cred::ssh_key(username, None, &default_ssh_key_path(), None);
+    }
+    if allowed_types.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+      if let Ok(token) = std::env::var("BEND_GIT_TOKEN") {
+        return git2::Cred::userpass_plaintext(&token, "");
+      }
+    }
+    git2::Cred::default()
+  });
+
   let mut fetch_opts = FetchOptions::new();
-  remote.fetch(&["refs/tags/*:refs/tags/*"], Some(&mut fetch_opts), None)?;
-  Ok(())
+  fetch_opts.remote_callbacks(callbacks);
+  fetch_opts
+}
+
+/// The default SSH private key `ssh-agent` didn't already have loaded.
+fn default_ssh_key_path() -> PathBuf {
+  let home = std::env::var("HOME").unwrap_or_default();
+  Path::new(&home).join(".ssh/id_rsa")
 }
 
 /// Deletes all local tags from the repository.
@@ -131,26 +456,66 @@ fn delete_local_tags(repo: &Repository) -> Result<(), git2::Error> {
   Ok(())
 }
 
-/// Retrieves the latest tag from the repository by parsing the tag names as versions
-/// and returning the highest version.
-fn get_latest_tag(repo: &Repository) -> Result<String, Box<dyn Error>> {
+/// Collects every tag in the repository whose name parses as a semver `Version`.
+fn tagged_versions(repo: &Repository) -> Result<Vec<Version>, git2::Error> {
   let refs = repo.references()?;
-  let mut latest_tag: Option<Version> = None;
+  let mut versions = Vec::new();
 
   for reference in refs {
     let reference = reference?;
     if reference.is_tag() {
       if let Some(tag) = reference.shorthand() {
         if let Ok(version) = Version::parse(tag) {
-          if latest_tag.as_ref().map_or(true, |latest| version > *latest) {
-            latest_tag = Some(version);
-          }
+          versions.push(version);
         }
       }
     }
   }
 
-  latest_tag.map(|v| v.to_string()).ok_or_else(|| "No tags found".into())
+  Ok(versions)
+}
+
+/// Retrieves the latest tag from the repository by parsing the tag names as versions
+/// and returning the highest version.
+fn get_latest_tag(repo: &Repository) -> Result<String, Box<dyn Error>> {
+  tagged_versions(repo)?.into_iter().max().map(|v| v.to_string()).ok_or_else(|| "No tags found".into())
+}
+
+/// Picks the highest published tag that satisfies `req_str`, reporting every
+/// available version when nothing matches.
+fn resolve_requirement(repo: &Repository, url: &str, req_str: &str) -> Result<String, Box<dyn Error>> {
+  let req = VersionReq::parse(req_str).map_err(|err| format!("invalid version requirement '{req_str}': {err}"))?;
+
+  let mut versions = tagged_versions(repo)?;
+  versions.sort();
+
+  versions.iter().rev().find(|version| req.matches(version)).map(|version| version.to_string()).ok_or_else(|| {
+    let available = versions.iter().map(Version::to_string).collect::<Vec<_>>().join(", ");
+    format!("No version on '{url}' satisfies '{req_str}' (available: {available})").into()
+  })
+}
+
+/// Checks out the resolved reference, reporting a clear error naming what was requested.
+fn checkout_reference(repo: &Repository, url: &str, reference: &GitReference) -> Result<(), Box<dyn Error>> {
+  match reference {
+    GitReference::Tag(tag) => {
+      checkout_tag(repo, tag).map_err(|err| describe_checkout_error(err, &format!("Version '{tag}'"), url))
+    }
+    GitReference::Branch(branch) => {
+      checkout_branch(repo, branch).map_err(|err| describe_checkout_error(err, &format!("Branch '{branch}'"), url))
+    }
+    GitReference::Rev(rev) => {
+      checkout_rev(repo, rev).map_err(|err| describe_checkout_error(err, &format!("Commit '{rev}'"), url))
+    }
+    GitReference::Default | GitReference::Req(_) => unreachable!("resolved to a tag before checkout"),
+  }
+}
+
+fn describe_checkout_error(err: git2::Error, what: &str, url: &str) -> Box<dyn Error> {
+  match err.class() {
+    git2::ErrorClass::Reference => format!("{what} not found on '{url}'").into(),
+    _ => err.message().into(),
+  }
 }
 
 /// Checks out the specified tag in the repository, updating the HEAD to point to the tag's commit.
@@ -169,25 +534,43 @@ fn checkout_tag(repo: &Repository, tag: &str) -> Result<(), git2::Error> {
   Ok(())
 }
 
+/// Checks out the remote-tracking branch `origin/<branch>`, fetched ahead of time by `refresh_refs`.
+fn checkout_branch(repo: &Repository, branch: &str) -> Result<(), git2::Error> {
+  let refname = format!("refs/remotes/origin/{branch}");
+  let object = repo.find_reference(&refname)?.peel(git2::ObjectType::Commit)?;
+  repo.checkout_tree(&object, None)?;
+  repo.set_head_detached(object.id())
+}
+
+/// Checks out a specific commit by SHA.
+fn checkout_rev(repo: &Repository, rev: &str) -> Result<(), git2::Error> {
+  let object = repo.revparse_single(rev)?;
+  repo.checkout_tree(&object, None)?;
+  repo.set_head_detached(object.id())
+}
+
 /// Updates the module configuration file with the dependency information.
-fn update_mod(name: &str, version: &str, alias: Option<String>) -> Result<(), Box<dyn Error>> {
+fn update_mod(name: &str, reference: &GitReference, alias: Option<String>) -> Result<(), Box<dyn Error>> {
   let mut config = get_config()?;
   let deps = get_deps(&mut config)?;
 
   match deps.get_mut(name) {
-    Some(dep_item) => update_existing_dependency(dep_item, version, alias),
-    None => _ = deps.insert(name, new_dependency(version, alias)),
+    Some(dep_item) => update_existing_dependency(dep_item, reference, alias),
+    None => _ = deps.insert(name, new_dependency(reference, alias)),
   }
 
   save_config(config)
 }
 
-/// Updates an existing dependency with the new version and alias (if provided).
-fn update_existing_dependency(dep_item: &mut Item, version: &str, alias: Option<String>) {
+/// Updates an existing dependency with the new reference and alias (if provided).
+fn update_existing_dependency(dep_item: &mut Item, reference: &GitReference, alias: Option<String>) {
   match dep_item.as_table_like_mut() {
-    None => *dep_item = new_dependency(version, alias),
+    None => *dep_item = new_dependency(reference, alias),
     Some(table) => {
-      table.insert("version", value(version));
+      for key in ["version", "branch", "rev"] {
+        table.remove(key);
+      }
+      table.insert(reference.toml_key(), value(reference_value(reference)));
       if let Some(alias) = alias {
         table.insert("alias", value(alias));
       } else {
@@ -197,15 +580,30 @@ fn update_existing_dependency(dep_item: &mut Item, version: &str, alias: Option<
   }
 }
 
-/// Creates a new dependency with the given version and alias (if provided).
-fn new_dependency(version: &str, alias: Option<String>) -> Item {
+/// Creates a new dependency with the given reference and alias (if provided).
+/// A plain tag or semver requirement with no alias is stored as a bare string
+/// (`"1.2.0"`, `"^1.2"`); anything else needs the `{ key = value }` table form
+/// to say which kind of ref it is.
+fn new_dependency(reference: &GitReference, alias: Option<String>) -> Item {
+  if alias.is_none() {
+    if let GitReference::Tag(v) | GitReference::Req(v) = reference {
+      return value(v);
+    }
+  }
+
+  let mut dep_table = Table::new();
+  dep_table[reference.toml_key()] = value(reference_value(reference));
   if let Some(alias) = alias {
-    let mut dep_table = Table::new();
-    dep_table["version"] = value(version);
     dep_table["alias"] = value(alias);
-    value(dep_table.into_inline_table())
-  } else {
-    value(version)
+  }
+  value(dep_table.into_inline_table())
+}
+
+/// The string payload carried by a resolved (non-`Default`) reference.
+fn reference_value(reference: &GitReference) -> &str {
+  match reference {
+    GitReference::Tag(v) | GitReference::Req(v) | GitReference::Branch(v) | GitReference::Rev(v) => v,
+    GitReference::Default => unreachable!("resolved before being persisted"),
   }
 }
 
@@ -226,9 +624,7 @@ fn remove_dep(name: &str) -> Result<(), Box<dyn Error>> {
 }
 
 fn remove_repo(name: &str) -> Result<(), Box<dyn Error>> {
-  let repo_name = repository_name(name);
-  let folder = format!(".bend/{}", repo_name);
-  let local_path = Path::new(&folder);
+  let local_path = dependency_path(name, None);
 
   if local_path.exists() {
     std::fs::remove_dir_all(local_path)?;
@@ -237,6 +633,153 @@ fn remove_repo(name: &str) -> Result<(), Box<dyn Error>> {
   Ok(())
 }
 
+/// Reconciles `.bend/` with the full transitive closure of the dependencies
+/// declared in `mod.toml`: clones or checks out anything missing or out of
+/// date (preferring the commit pinned in `mod.lock` unless `update` is set),
+/// prunes any checkout that is no longer part of that closure, rewrites
+/// `mod.lock`, and reports what changed.
+fn tidy(update: bool) -> Result<(), Box<dyn Error>> {
+  let mut config = get_config()?;
+  let deps = get_deps(&mut config)?;
+
+  let roots = deps
+    .iter()
+    .map(|(name, dep_item)| {
+      let (reference, alias) = dependency_spec(dep_item);
+      (name.to_string(), reference, alias)
+    })
+    .collect();
+
+  let lockfile = get_lockfile()?;
+  let installs = install_graph(roots, &lockfile, update)?;
+
+  let mut new_lockfile = DocumentMut::new();
+  let mut kept = Vec::new();
+  let mut added = Vec::new();
+  let mut updated = Vec::new();
+
+  for install in &installs {
+    let repo_name = install.alias.clone().unwrap_or_else(|| repository_name(&install.name).to_string());
+    let url = dependency_url(&install.name);
+    new_lockfile.insert(&install.name, lock_entry(&install.resolved, &url, install.commit));
+
+    if install.added {
+      added.push(repo_name.clone());
+    } else if install.updated {
+      updated.push(repo_name.clone());
+    }
+    kept.push(repo_name);
+  }
+
+  let removed = prune_repos(&kept)?;
+  save_lockfile(new_lockfile)?;
+
+  report_tidy(&added, &updated, &removed);
+  Ok(())
+}
+
+/// Extracts the recorded reference and alias from a dependency entry, supporting
+/// both the plain string form (an exact tag or a semver requirement) and the `{ version | branch | rev
+/// = ..., alias = ... }` table form.
+fn dependency_spec(item: &Item) -> (GitReference, Option<String>) {
+  match item.as_table_like() {
+    Some(table) => {
+      let alias = table.get("alias").and_then(|v| v.as_str()).map(String::from);
+      let reference = [
+        ("version", GitReference::from_version_string as fn(String) -> GitReference),
+        ("branch", GitReference::Branch),
+        ("rev", GitReference::Rev),
+      ]
+      .into_iter()
+      .find_map(|(key, make)| table.get(key).and_then(|v| v.as_str()).map(|v| make(v.to_string())))
+      .unwrap_or(GitReference::Default);
+      (reference, alias)
+    }
+    None => (item.as_str().map_or(GitReference::Default, |v| GitReference::from_version_string(v.to_string())), None),
+  }
+}
+
+/// Returns the commit the repository at `local_path` currently has checked out,
+/// or `None` if there is no checkout there yet.
+fn current_commit(local_path: &Path) -> Option<git2::Oid> {
+  Repository::open(local_path).ok()?.head().ok()?.target()
+}
+
+/// Removes any directory under `.bend/` that isn't one of the `keep` checkouts,
+/// returning the names of everything that was removed.
+fn prune_repos(keep: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+  let mut removed = Vec::new();
+  let bend_dir = Path::new(".bend");
+  if !bend_dir.exists() {
+    return Ok(removed);
+  }
+
+  for entry in std::fs::read_dir(bend_dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if entry.file_type()?.is_dir() && !keep.contains(&name) {
+      std::fs::remove_dir_all(entry.path())?;
+      removed.push(name);
+    }
+  }
+
+  Ok(removed)
+}
+
+/// Prints a summary of what `tidy` added, updated, or removed.
+fn report_tidy(added: &[String], updated: &[String], removed: &[String]) {
+  if added.is_empty() && updated.is_empty() && removed.is_empty() {
+    println!("Already up to date.");
+    return;
+  }
+
+  for name in added {
+    println!("Added   {name}");
+  }
+  for name in updated {
+    println!("Updated {name}");
+  }
+  for name in removed {
+    println!("Removed {name}");
+  }
+}
+
+const LOCKFILE: &str = "mod.lock";
+
+/// Reads `mod.lock`, returning an empty document if it doesn't exist yet.
+fn get_lockfile() -> Result<DocumentMut, Box<dyn Error>> {
+  match std::fs::read_to_string(LOCKFILE) {
+    Ok(file) => file.parse::<DocumentMut>().map_err(|_| "invalid 'mod.lock' format".into()),
+    Err(_) => Ok(DocumentMut::new()),
+  }
+}
+
+/// Writes `mod.lock`, creating it if it doesn't exist yet.
+fn save_lockfile(lockfile: DocumentMut) -> Result<(), Box<dyn Error>> {
+  let mut f = OpenOptions::new().write(true).create(true).truncate(true).open(LOCKFILE)?;
+  write!(f, "{}", lockfile)?;
+  Ok(())
+}
+
+/// Looks up how `name` was resolved last time, if `mod.lock` has an entry for
+/// it: the human-readable version it was resolved to and the commit it was
+/// locked at.
+fn locked_entry(lockfile: &DocumentMut, name: &str) -> Option<(String, String)> {
+  let entry = lockfile.get(name)?.as_table_like()?;
+  let version = entry.get("version")?.as_str()?.to_string();
+  let commit = entry.get("commit")?.as_str()?.to_string();
+  Some((version, commit))
+}
+
+/// Builds the `mod.lock` entry recording how a dependency was resolved.
+fn lock_entry(reference: &GitReference, url: &str, commit: git2::Oid) -> Item {
+  let mut entry = Table::new();
+  entry["version"] = value(reference_value(reference));
+  entry["source_url"] = value(url);
+  entry["commit"] = value(commit.to_string());
+  Item::Table(entry)
+}
+
 fn get_config() -> Result<DocumentMut, Box<dyn Error>> {
   let file = std::fs::read_to_string("mod.toml")?;
   file.parse::<DocumentMut>().map_err(|_| "invalid 'mod.toml' format".into())